@@ -4,24 +4,56 @@ use crypto::sha3::Sha3;
 use hex::{FromHex, FromHexError, ToHex};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
+use std::ops::{Add, Div};
+use std::path::Path;
 use std::{
     fmt::Debug,
     fmt::Display,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+/// Genesis difficulty used when no prior block exists to inherit one from.
+const INITIAL_DIFFICULTY: u64 = 131_072;
+
+/// Default block time, in seconds, the difficulty retarget aims to hold.
+const DEFAULT_TARGET_SECS: u64 = 13;
+
+/// Default divisor controlling how sharply difficulty reacts to drift from
+/// `target_secs` (larger = gentler adjustment per block).
+const DEFAULT_DIFFICULTY_BOUND_DIVISOR: u64 = 2048;
+
 pub struct Config {
     pub genesis_file: Option<String>,
+    pub datadir: Option<String>,
+    pub target_secs: u64,
+    pub minimum_difficulty: u64,
+    pub difficulty_bound_divisor: u64,
 }
 
 impl Config {
     pub fn new(mut args: impl Iterator<Item = String>) -> Result<Self, &'static str> {
         args.next();
 
-        let genesis_file = args.next();
+        let mut genesis_file = None;
+        let mut datadir = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--datadir" => datadir = args.next(),
+                _ => genesis_file = Some(arg),
+            }
+        }
 
-        Ok(Config { genesis_file })
+        Ok(Config {
+            genesis_file,
+            datadir,
+            target_secs: DEFAULT_TARGET_SECS,
+            minimum_difficulty: INITIAL_DIFFICULTY,
+            difficulty_bound_divisor: DEFAULT_DIFFICULTY_BOUND_DIVISOR,
+        })
     }
 }
 
@@ -46,10 +78,38 @@ pub fn parse_hex_string_as_address(hex_string: &str) -> Result<Address, FromHexE
     Ok(Bytes(bytes))
 }
 
-pub fn init_genesis(filename: &str) -> Result<Block, &'static str> {
+pub fn init_genesis(filename: &str, config: &mut Config) -> Result<(Block, State), &'static str> {
     let json = fs::read_to_string(filename).unwrap();
     let genesis: Value = serde_json::from_str(&json).unwrap();
 
+    if let Some(duration_limit) = genesis["durationLimit"].as_u64() {
+        if duration_limit == 0 {
+            return Err("Unable to process genesis file: durationLimit must be greater than zero");
+        }
+
+        config.target_secs = duration_limit;
+    }
+
+    if let Some(minimum_difficulty) = genesis["minimumDifficulty"].as_u64() {
+        if minimum_difficulty == 0 {
+            return Err(
+                "Unable to process genesis file: minimumDifficulty must be greater than zero",
+            );
+        }
+
+        config.minimum_difficulty = minimum_difficulty;
+    }
+
+    if let Some(difficulty_bound_divisor) = genesis["difficultyBoundDivisor"].as_u64() {
+        if difficulty_bound_divisor == 0 {
+            return Err(
+                "Unable to process genesis file: difficultyBoundDivisor must be greater than zero",
+            );
+        }
+
+        config.difficulty_bound_divisor = difficulty_bound_divisor;
+    }
+
     let mut transactions: Vec<Transaction> = Vec::new();
 
     for tx in genesis["transactions"].as_array().unwrap().iter() {
@@ -68,33 +128,75 @@ pub fn init_genesis(filename: &str) -> Result<Block, &'static str> {
         };
     }
 
+    let mut state = State::new();
+
+    if let Some(alloc) = genesis["alloc"].as_object() {
+        for (address, account) in alloc.iter() {
+            let address =
+                parse_hex_string_as_address(address).map_err(|_| "Unable to process genesis file")?;
+            let balance = account["balance"]
+                .as_str()
+                .and_then(|balance| balance.parse::<u64>().ok())
+                .or_else(|| account["balance"].as_u64())
+                .ok_or("Unable to process genesis file")?;
+
+            state.credit(&address, balance);
+        }
+    }
+
     let mut genesis = Block {
         hash: Default::default(),
         parent_hash: Default::default(),
         transactions,
+        transactions_root: Default::default(),
+        state_root: Default::default(),
         timestamp: genesis["timestamp"].as_u64().unwrap(),
+        difficulty: config.minimum_difficulty,
+        nonce: 0,
     };
 
+    genesis.transactions_root = genesis.merkle_root();
+    genesis.state_root = state.state_root();
     genesis.hash = genesis.hash_block(0);
 
-    Ok(genesis)
+    Ok((genesis, state))
 }
 
-pub fn run(config: Config) {
-    let genesis = match config.genesis_file {
-        Some(filename) => init_genesis(&filename).unwrap(),
-        None => Block {
-            hash: Default::default(),
-            parent_hash: Default::default(),
-            transactions: Default::default(),
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
-        },
-    };
+pub fn run(mut config: Config) {
+    let chain_path = config
+        .datadir
+        .as_ref()
+        .map(|datadir| format!("{}/chain.bin", datadir));
+
+    let existing = chain_path.as_deref().filter(|path| Path::new(path).exists());
 
-    let mut blockchain = Blockchain::new(genesis);
+    let mut blockchain = match existing.map(Blockchain::load) {
+        Some(Ok(blockchain)) => blockchain,
+        Some(Err(err)) => panic!("Unable to load existing chain file: {}", err),
+        None => {
+            let (genesis, state) = match config.genesis_file.clone() {
+                Some(filename) => init_genesis(&filename, &mut config).unwrap(),
+                None => (
+                    Block {
+                        hash: Default::default(),
+                        parent_hash: Default::default(),
+                        transactions: Default::default(),
+                        transactions_root: Default::default(),
+                        state_root: Default::default(),
+                        timestamp: SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        difficulty: config.minimum_difficulty,
+                        nonce: 0,
+                    },
+                    State::new(),
+                ),
+            };
+
+            Blockchain::new(genesis, state, &config)
+        }
+    };
 
     loop {
         let block = blockchain.add_new_block();
@@ -104,45 +206,85 @@ pub fn run(config: Config) {
             "Block hash: {} | Timestamp: {}",
             block.hash, block.timestamp
         );
+
+        if let (Some(datadir), Some(path)) = (&config.datadir, &chain_path) {
+            fs::create_dir_all(datadir).unwrap();
+            blockchain.save(path).unwrap();
+        }
     }
 }
 
 pub struct Blockchain {
     pub chain: Vec<Block>,
     pub transactions: Vec<Transaction>,
+    pub state: State,
+    block_index: HashMap<Bytes32, usize>,
+    pub target_secs: u64,
+    pub minimum_difficulty: u64,
+    pub difficulty_bound_divisor: u64,
 }
 
 impl Blockchain {
-    pub fn new(genesis: Block) -> Self {
+    pub fn new(genesis: Block, state: State, config: &Config) -> Self {
+        let mut block_index = HashMap::new();
+        block_index.insert(genesis.hash, 0);
+
         Blockchain {
             chain: vec![genesis],
             transactions: Default::default(),
+            state,
+            block_index,
+            target_secs: config.target_secs,
+            minimum_difficulty: config.minimum_difficulty,
+            difficulty_bound_divisor: config.difficulty_bound_divisor,
         }
     }
 
+    /// O(1) lookup of a block by its hash, e.g. to resolve a `parent_hash`
+    /// when validating or reorganizing the chain.
+    pub fn block_by_hash(&self, hash: &Bytes32) -> Option<&Block> {
+        self.block_index.get(hash).map(|&index| &self.chain[index])
+    }
+
     pub fn add_pending_transaction(&mut self, transaction: Transaction) {
         self.transactions.push(transaction);
     }
 
     pub fn mine(&self, block: &mut Block) -> Bytes32 {
+        let target = block.target();
         let mut nonce: i64 = 0;
 
         block.hash = loop {
             let hash = block.hash_block(nonce);
+            let hash_int = U256::from_big_endian_bytes(&hash.0);
 
-            if hash.as_ref().starts_with(&[0, 0]) {
+            if hash_int <= target {
                 break hash;
             }
 
             nonce += 1;
         };
+        block.nonce = nonce;
 
         block.hash
     }
 
     pub fn create_block(&self) -> Block {
         let latest = &self.chain[self.chain.len() - 1];
-        let mut new = Block::new(latest.hash);
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let delta = timestamp.saturating_sub(latest.timestamp);
+        let difficulty = retarget_difficulty(
+            latest.difficulty,
+            delta,
+            self.target_secs,
+            self.minimum_difficulty,
+            self.difficulty_bound_divisor,
+        );
+
+        let mut new = Block::new(latest.hash, difficulty, timestamp);
 
         let mut tx_iter = self.transactions.iter();
 
@@ -152,15 +294,122 @@ impl Blockchain {
             }
         }
 
+        new.transactions_root = new.merkle_root();
+
         new
     }
 
     pub fn add_new_block(&mut self) -> Block {
         let mut block = self.create_block();
+
+        let mut applied = Vec::with_capacity(block.transactions.len());
+
+        for tx in &block.transactions {
+            if self.state.apply_transaction(tx) {
+                applied.push(tx.clone());
+            }
+        }
+
+        block.set_transactions(applied.clone());
+        block.state_root = self.state.state_root();
+
         self.mine(&mut block);
         self.chain.push(block.clone());
+        self.block_index.insert(block.hash, self.chain.len() - 1);
+        self.transactions.retain(|tx| !applied.contains(tx));
+
         block
     }
+
+    /// Bincode-serializes the chain, pending transactions, account state,
+    /// and retarget config to `path`.
+    pub fn save(&self, path: &str) -> Result<(), &'static str> {
+        let snapshot = ChainSnapshot {
+            chain: self.chain.clone(),
+            transactions: self.transactions.clone(),
+            state: self.state.clone(),
+            target_secs: self.target_secs,
+            minimum_difficulty: self.minimum_difficulty,
+            difficulty_bound_divisor: self.difficulty_bound_divisor,
+        };
+
+        let encoded = bincode::serialize(&snapshot).map_err(|_| "Unable to encode chain")?;
+        fs::write(path, encoded).map_err(|_| "Unable to write chain file")?;
+
+        Ok(())
+    }
+
+    /// Deserializes a chain saved by `save` and revalidates it: every
+    /// block's `parent_hash` must match the previous block's `hash`, and
+    /// every block but the genesis (which is never mined) must satisfy its
+    /// own proof of work. The account state and retarget config are
+    /// restored from the snapshot directly rather than replayed, so balances
+    /// seeded from genesis `alloc` (which are never recorded as
+    /// `Transaction`s) aren't lost on reload.
+    pub fn load(path: &str) -> Result<Self, &'static str> {
+        let bytes = fs::read(path).map_err(|_| "Unable to read chain file")?;
+        let snapshot: ChainSnapshot =
+            bincode::deserialize(&bytes).map_err(|_| "Unable to decode chain file")?;
+
+        if snapshot.chain.is_empty() {
+            return Err("Chain file contains no blocks");
+        }
+
+        for pair in snapshot.chain.windows(2) {
+            if pair[1].parent_hash != pair[0].hash {
+                return Err("Chain file is corrupt: parent_hash mismatch");
+            }
+        }
+
+        for block in snapshot.chain.iter().skip(1) {
+            if !block.verify_pow() {
+                return Err("Chain file is corrupt: invalid proof of work");
+            }
+        }
+
+        let mut block_index = HashMap::new();
+        for (index, block) in snapshot.chain.iter().enumerate() {
+            block_index.insert(block.hash, index);
+        }
+
+        Ok(Blockchain {
+            chain: snapshot.chain,
+            transactions: snapshot.transactions,
+            state: snapshot.state,
+            block_index,
+            target_secs: snapshot.target_secs,
+            minimum_difficulty: snapshot.minimum_difficulty,
+            difficulty_bound_divisor: snapshot.difficulty_bound_divisor,
+        })
+    }
+}
+
+/// On-disk representation written by `Blockchain::save`: everything needed
+/// to rebuild a `Blockchain` other than its derived indexes.
+#[derive(Deserialize, Serialize)]
+struct ChainSnapshot {
+    chain: Vec<Block>,
+    transactions: Vec<Transaction>,
+    state: State,
+    target_secs: u64,
+    minimum_difficulty: u64,
+    difficulty_bound_divisor: u64,
+}
+
+/// Ethereum-style difficulty retargeting: nudges `parent_difficulty` toward
+/// keeping block production at `target_secs`, clamped to `minimum_difficulty`.
+fn retarget_difficulty(
+    parent_difficulty: u64,
+    delta: u64,
+    target_secs: u64,
+    minimum_difficulty: u64,
+    difficulty_bound_divisor: u64,
+) -> u64 {
+    let adjustment = std::cmp::max(1 - (delta / target_secs) as i64, -99);
+    let change = (parent_difficulty / difficulty_bound_divisor) as i64 * adjustment;
+    let new_difficulty = parent_difficulty as i64 + change;
+
+    new_difficulty.max(minimum_difficulty as i64) as u64
 }
 
 #[derive(Clone, PartialEq, Default, Copy, Deserialize, Serialize)]
@@ -188,39 +437,309 @@ macro_rules! impl_traits_for_bytes {
             fn as_ref(&self) -> &[u8] {
                 &self.0
             }
+        }
+
+        impl Eq for $t {}
+
+        impl std::hash::Hash for $t {
+            fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+                self.0.hash(state);
+            }
         })*
     };
 }
 
 impl_traits_for_bytes!(for Bytes32, Address);
 
-#[derive(Debug, PartialEq, Clone, Serialize)]
+fn keccak256(bytes: &[u8]) -> Bytes32 {
+    let mut hash = [0u8; 32];
+    let mut hasher = Sha3::keccak256();
+
+    hasher.input(bytes);
+    hasher.result(&mut hash);
+
+    Bytes(hash)
+}
+
+fn hash_pair(left: &Bytes32, right: &Bytes32) -> Bytes32 {
+    let mut bytes = Vec::with_capacity(64);
+    bytes.extend_from_slice(&left.0);
+    bytes.extend_from_slice(&right.0);
+
+    keccak256(&bytes)
+}
+
+/// Re-hashes `leaf` up through `proof` (each step tagged with whether the
+/// sibling sits on the left or right) and checks the result matches `root`,
+/// letting a client confirm inclusion without the full transaction set.
+pub fn verify_merkle_proof(leaf: Bytes32, proof: &[(Bytes32, bool)], root: Bytes32) -> bool {
+    let computed = proof.iter().fold(leaf, |node, (sibling, sibling_is_left)| {
+        if *sibling_is_left {
+            hash_pair(sibling, &node)
+        } else {
+            hash_pair(&node, sibling)
+        }
+    });
+
+    computed == root
+}
+
+/// A 256-bit unsigned integer stored as four 64-bit limbs, least-significant
+/// limb first. Used to express PoW difficulty targets at full Bitcoin/
+/// Ethereum precision rather than whole-byte steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct U256([u64; 4]);
+
+impl U256 {
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+    pub const ZERO: U256 = U256([0; 4]);
+
+    pub fn from_big_endian_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let chunk: [u8; 8] = bytes[i * 8..i * 8 + 8].try_into().unwrap();
+            *limb = u64::from_be_bytes(chunk);
+        }
+
+        limbs.reverse();
+        U256(limbs)
+    }
+
+    pub fn to_big_endian_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+
+        for (chunk, limb) in bytes.chunks_mut(8).zip(self.0.iter().rev()) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+
+        bytes
+    }
+
+    fn bit(&self, index: u32) -> bool {
+        (self.0[(index / 64) as usize] >> (index % 64)) & 1 == 1
+    }
+
+    fn set_bit(&mut self, index: u32) {
+        self.0[(index / 64) as usize] |= 1u64 << (index % 64);
+    }
+
+    fn shl1(&self) -> Self {
+        let mut out = [0u64; 4];
+        let mut carry = 0u64;
+
+        for (out, limb) in out.iter_mut().zip(self.0.iter()) {
+            *out = (limb << 1) | carry;
+            carry = limb >> 63;
+        }
+
+        U256(out)
+    }
+
+    fn sub(&self, other: &Self) -> Self {
+        let mut out = [0u64; 4];
+        let mut borrow = false;
+
+        for (out, (a, b)) in out.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            let (r1, b1) = a.overflowing_sub(*b);
+            let (r2, b2) = r1.overflowing_sub(borrow as u64);
+            *out = r2;
+            borrow = b1 || b2;
+        }
+
+        U256(out)
+    }
+}
+
+impl From<u64> for U256 {
+    fn from(value: u64) -> Self {
+        U256([value, 0, 0, 0])
+    }
+}
+
+impl Add for U256 {
+    type Output = U256;
+
+    fn add(self, other: U256) -> U256 {
+        let mut out = [0u64; 4];
+        let mut carry = 0u64;
+
+        for (out, (a, b)) in out.iter_mut().zip(self.0.iter().zip(other.0.iter())) {
+            let (r1, c1) = a.overflowing_add(*b);
+            let (r2, c2) = r1.overflowing_add(carry);
+            *out = r2;
+            carry = (c1 as u64) + (c2 as u64);
+        }
+
+        U256(out)
+    }
+}
+
+impl Div for U256 {
+    type Output = U256;
+
+    /// Schoolbook binary long division: shift a bit of `self` into a running
+    /// remainder at a time and subtract the divisor whenever it fits.
+    fn div(self, divisor: U256) -> U256 {
+        assert!(divisor != U256::ZERO, "division by zero");
+
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+
+        for i in (0..256u32).rev() {
+            remainder = remainder.shl1();
+
+            if self.bit(i) {
+                remainder.set_bit(0);
+            }
+
+            if remainder >= divisor {
+                remainder = remainder.sub(&divisor);
+                quotient.set_bit(i);
+            }
+        }
+
+        quotient
+    }
+}
+
+impl PartialOrd for U256 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for U256 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        for i in (0..4).rev() {
+            match self.0[i].cmp(&other.0[i]) {
+                Ordering::Equal => continue,
+                ord => return ord,
+            }
+        }
+
+        Ordering::Equal
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 pub struct Block {
     pub hash: Bytes32,
     pub parent_hash: Bytes32,
     pub transactions: Vec<Transaction>,
+    pub transactions_root: Bytes32,
+    pub state_root: Bytes32,
     pub timestamp: u64,
+    pub difficulty: u64,
+    pub nonce: i64,
+}
+
+/// What actually goes into `hash_block`: the transaction set is represented
+/// by its Merkle root rather than serialized in full. `hash` itself is
+/// omitted, since it is the output of `hash_block`; this is what lets
+/// `verify_pow` recompute the hash of a block whose `hash` is already set.
+#[derive(Serialize)]
+struct BlockHeader {
+    parent_hash: Bytes32,
+    transactions_root: Bytes32,
+    state_root: Bytes32,
+    timestamp: u64,
+    difficulty: u64,
 }
 
 impl Block {
-    pub fn new(parent_hash: Bytes32) -> Self {
+    pub fn new(parent_hash: Bytes32, difficulty: u64, timestamp: u64) -> Self {
         Block {
             hash: Default::default(),
             parent_hash,
             transactions: Default::default(),
-            timestamp: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs(),
+            transactions_root: Default::default(),
+            state_root: Default::default(),
+            timestamp,
+            difficulty,
+            nonce: 0,
         }
     }
 
     pub fn set_transactions(&mut self, transactions: Vec<Transaction>) {
         self.transactions = transactions;
+        self.transactions_root = self.merkle_root();
+    }
+
+    /// Hashes each transaction, then repeatedly pairs and re-hashes adjacent
+    /// nodes (duplicating the last one when the level is odd-sized, as
+    /// Bitcoin does) until a single root remains. An empty block's root is
+    /// the all-zero hash.
+    pub fn merkle_root(&self) -> Bytes32 {
+        if self.transactions.is_empty() {
+            return Bytes32::default();
+        }
+
+        let mut level: Vec<Bytes32> = self
+            .transactions
+            .iter()
+            .map(|tx| keccak256(&bincode::serialize(tx).unwrap()))
+            .collect();
+
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).unwrap_or(&pair[0]);
+                    hash_pair(&pair[0], right)
+                })
+                .collect();
+        }
+
+        level[0]
+    }
+
+    /// Sibling hashes along the path from transaction `tx_index` up to
+    /// `transactions_root`, each tagged with whether the sibling is the left
+    /// or right child, so a light client can confirm inclusion without the
+    /// full transaction set. `None` if `tx_index` is out of range.
+    pub fn merkle_proof(&self, tx_index: usize) -> Option<Vec<(Bytes32, bool)>> {
+        if tx_index >= self.transactions.len() {
+            return None;
+        }
+
+        let mut level: Vec<Bytes32> = self
+            .transactions
+            .iter()
+            .map(|tx| keccak256(&bincode::serialize(tx).unwrap()))
+            .collect();
+        let mut index = tx_index;
+        let mut proof = Vec::new();
+
+        while level.len() > 1 {
+            let sibling_is_left = index % 2 == 1;
+            let sibling_index = if sibling_is_left { index - 1 } else { index + 1 };
+            let sibling = *level.get(sibling_index).unwrap_or(&level[index]);
+            proof.push((sibling, sibling_is_left));
+
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).unwrap_or(&pair[0]);
+                    hash_pair(&pair[0], right)
+                })
+                .collect();
+
+            index /= 2;
+        }
+
+        Some(proof)
     }
 
     pub fn hash_block(&self, nonce: i64) -> Bytes32 {
-        let encoded = bincode::serialize(&self).unwrap();
+        let header = BlockHeader {
+            parent_hash: self.parent_hash,
+            transactions_root: self.transactions_root,
+            state_root: self.state_root,
+            timestamp: self.timestamp,
+            difficulty: self.difficulty,
+        };
+        let encoded = bincode::serialize(&header).unwrap();
 
         let mut hash = [0u8; 32];
         let mut hasher = Sha3::keccak256();
@@ -231,6 +750,22 @@ impl Block {
 
         Bytes(hash)
     }
+
+    /// The 256-bit PoW target derived from `difficulty`: lower difficulty
+    /// values yield a larger (easier) target, mirroring Bitcoin/Ethereum.
+    pub fn target(&self) -> U256 {
+        U256::MAX / U256::from(self.difficulty)
+    }
+
+    /// Recomputes the hash at the stored `nonce` and checks it both matches
+    /// the stored `hash` and satisfies the difficulty target, so a node can
+    /// validate work instead of re-mining it.
+    pub fn verify_pow(&self) -> bool {
+        let hash = self.hash_block(self.nonce);
+        let hash_int = U256::from_big_endian_bytes(&hash.0);
+
+        hash == self.hash && hash_int <= self.target()
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Default, Deserialize, Serialize)]
@@ -241,6 +776,92 @@ pub struct Transaction {
     pub hash: Bytes32,
 }
 
+/// An Ethereum-style account: what a `Transaction` debits, credits, and
+/// increments on every successful transfer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+pub struct Account {
+    pub balance: u64,
+    pub nonce: u64,
+}
+
+/// The global account store: a `HashMap` keyed by `Address`, mirroring the
+/// O(1) `block_index` lookup `Blockchain` uses for blocks. `state_root` is a
+/// sort-then-hash commitment over the entries, not an actual
+/// Merkle-Patricia trie.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct State {
+    accounts: HashMap<Address, Account>,
+}
+
+impl State {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn get_account(&self, address: &Address) -> Account {
+        self.accounts.get(address).copied().unwrap_or_default()
+    }
+
+    pub fn get_balance(&self, address: &Address) -> u64 {
+        self.get_account(address).balance
+    }
+
+    fn account_mut(&mut self, address: &Address) -> &mut Account {
+        self.accounts.entry(*address).or_default()
+    }
+
+    pub fn credit(&mut self, address: &Address, amount: u64) {
+        self.account_mut(address).balance += amount;
+    }
+
+    /// Applies `transaction` (debit `from`, credit `to`, bump `from`'s
+    /// nonce). Returns `false` without changing anything if `from` cannot
+    /// cover `value`.
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> bool {
+        let value = transaction.value as u64;
+
+        if self.get_balance(&transaction.from) < value {
+            return false;
+        }
+
+        self.account_mut(&transaction.from).balance -= value;
+        self.account_mut(&transaction.from).nonce += 1;
+        self.credit(&transaction.to, value);
+
+        true
+    }
+
+    /// Hashes every account in address order (so the root doesn't depend on
+    /// `HashMap` iteration order), then folds the hashes pairwise like
+    /// `Block::merkle_root` — a flat hash commitment, not a
+    /// Merkle-Patricia trie.
+    pub fn state_root(&self) -> Bytes32 {
+        if self.accounts.is_empty() {
+            return Bytes32::default();
+        }
+
+        let mut entries: Vec<(&Address, &Account)> = self.accounts.iter().collect();
+        entries.sort_by_key(|(address, _)| address.0);
+
+        let mut level: Vec<Bytes32> = entries
+            .iter()
+            .map(|entry| keccak256(&bincode::serialize(entry).unwrap()))
+            .collect();
+
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| {
+                    let right = pair.get(1).unwrap_or(&pair[0]);
+                    hash_pair(&pair[0], right)
+                })
+                .collect();
+        }
+
+        level[0]
+    }
+}
+
 // mod tests {
 //     use super::*;
 //     use std::clone;
@@ -302,3 +923,423 @@ pub struct Transaction {
 //     assert_eq!(bytes, Bytes32(test_bytes));
 // }
 // }
+
+#[cfg(test)]
+mod u256_tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_big_endian_bytes() {
+        let bytes: [u8; 32] = [
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c, 0x0d, 0x0e,
+            0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c,
+            0x1d, 0x1e, 0x1f, 0x20,
+        ];
+
+        let value = U256::from_big_endian_bytes(&bytes);
+
+        assert_eq!(value.to_big_endian_bytes(), bytes);
+    }
+
+    #[test]
+    fn zero_round_trips() {
+        let bytes = [0u8; 32];
+
+        assert_eq!(U256::from_big_endian_bytes(&bytes), U256::ZERO);
+        assert_eq!(U256::ZERO.to_big_endian_bytes(), bytes);
+    }
+
+    #[test]
+    fn orders_by_most_significant_limb_first() {
+        let low = U256::from(1u64);
+        let high = U256::MAX;
+
+        assert!(low < high);
+        assert!(high > low);
+        assert_eq!(low, low);
+        // A larger low limb must not outweigh a larger high limb.
+        assert!(U256([u64::MAX, 0, 0, 0]) < U256([0, 1, 0, 0]));
+    }
+
+    #[test]
+    fn adds_with_carry_across_limbs() {
+        let max_limb = U256::from(u64::MAX);
+
+        assert_eq!(max_limb + U256::from(1), U256([0, 1, 0, 0]));
+    }
+
+    #[test]
+    fn divides_evenly() {
+        let dividend = U256::from(100);
+        let divisor = U256::from(5);
+
+        assert_eq!(dividend / divisor, U256::from(20));
+    }
+
+    #[test]
+    fn divides_with_truncation() {
+        let dividend = U256::from(7);
+        let divisor = U256::from(2);
+
+        assert_eq!(dividend / divisor, U256::from(3));
+    }
+
+    #[test]
+    fn divides_full_width_value() {
+        assert_eq!(U256::MAX / U256::from(1), U256::MAX);
+    }
+}
+
+#[cfg(test)]
+mod persistence_tests {
+    use super::*;
+
+    fn temp_chain_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!(
+                "simple-blockchain-test-{}-{}.bin",
+                name,
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn genesis_block(difficulty: u64) -> Block {
+        let mut block = Block::new(Bytes32::default(), difficulty, 0);
+        block.hash = block.hash_block(0);
+        block
+    }
+
+    #[test]
+    fn save_and_load_preserves_alloc_only_balances() {
+        let path = temp_chain_path("alloc");
+        let address: Address = Bytes([7u8; 20]);
+
+        let config = Config {
+            genesis_file: None,
+            datadir: None,
+            target_secs: DEFAULT_TARGET_SECS,
+            minimum_difficulty: INITIAL_DIFFICULTY,
+            difficulty_bound_divisor: DEFAULT_DIFFICULTY_BOUND_DIVISOR,
+        };
+
+        let mut state = State::new();
+        state.credit(&address, 500);
+
+        let blockchain = Blockchain::new(genesis_block(config.minimum_difficulty), state, &config);
+        blockchain.save(&path).unwrap();
+
+        let loaded = Blockchain::load(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        // `address` was credited directly via `State::credit`, never through a
+        // `Transaction`, so replaying transactions on load would lose it.
+        assert_eq!(loaded.state.get_balance(&address), 500);
+    }
+
+    #[test]
+    fn save_and_load_preserves_retarget_config() {
+        let path = temp_chain_path("config");
+
+        let config = Config {
+            genesis_file: None,
+            datadir: None,
+            target_secs: 42,
+            minimum_difficulty: 99,
+            difficulty_bound_divisor: 7,
+        };
+
+        let blockchain = Blockchain::new(genesis_block(config.minimum_difficulty), State::new(), &config);
+        blockchain.save(&path).unwrap();
+
+        let loaded = Blockchain::load(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        assert_eq!(loaded.target_secs, 42);
+        assert_eq!(loaded.minimum_difficulty, 99);
+        assert_eq!(loaded.difficulty_bound_divisor, 7);
+    }
+
+    #[test]
+    fn load_rejects_corrupt_parent_hash_chain() {
+        let path = temp_chain_path("corrupt");
+
+        let config = Config {
+            genesis_file: None,
+            datadir: None,
+            target_secs: DEFAULT_TARGET_SECS,
+            minimum_difficulty: INITIAL_DIFFICULTY,
+            difficulty_bound_divisor: DEFAULT_DIFFICULTY_BOUND_DIVISOR,
+        };
+
+        let mut blockchain =
+            Blockchain::new(genesis_block(config.minimum_difficulty), State::new(), &config);
+        blockchain.add_new_block();
+        blockchain.chain[1].parent_hash = Bytes32::default();
+        blockchain.save(&path).unwrap();
+
+        let result = Blockchain::load(&path);
+        fs::remove_file(&path).ok();
+
+        assert!(result.is_err());
+    }
+}
+
+#[cfg(test)]
+mod merkle_proof_tests {
+    use super::*;
+
+    fn block_with_txs(count: u32) -> Block {
+        let mut block = Block::new(Bytes32::default(), INITIAL_DIFFICULTY, 0);
+        let transactions = (0..count)
+            .map(|value| Transaction {
+                value,
+                ..Default::default()
+            })
+            .collect();
+
+        block.set_transactions(transactions);
+        block
+    }
+
+    #[test]
+    fn proof_verifies_for_every_leaf_in_an_even_count_block() {
+        let block = block_with_txs(4);
+
+        for index in 0..4 {
+            let leaf = keccak256(&bincode::serialize(&block.transactions[index]).unwrap());
+            let proof = block.merkle_proof(index).unwrap();
+
+            assert!(verify_merkle_proof(leaf, &proof, block.transactions_root));
+        }
+    }
+
+    #[test]
+    fn proof_verifies_for_every_leaf_in_an_odd_count_block() {
+        let block = block_with_txs(5);
+
+        for index in 0..5 {
+            let leaf = keccak256(&bincode::serialize(&block.transactions[index]).unwrap());
+            let proof = block.merkle_proof(index).unwrap();
+
+            assert!(verify_merkle_proof(leaf, &proof, block.transactions_root));
+        }
+    }
+
+    #[test]
+    fn proof_verifies_for_a_single_transaction_block() {
+        let block = block_with_txs(1);
+        let leaf = keccak256(&bincode::serialize(&block.transactions[0]).unwrap());
+        let proof = block.merkle_proof(0).unwrap();
+
+        assert!(proof.is_empty());
+        assert!(verify_merkle_proof(leaf, &proof, block.transactions_root));
+    }
+
+    #[test]
+    fn proof_rejects_wrong_leaf() {
+        let block = block_with_txs(4);
+        let wrong_leaf = keccak256(&bincode::serialize(&block.transactions[1]).unwrap());
+        let proof = block.merkle_proof(0).unwrap();
+
+        assert!(!verify_merkle_proof(wrong_leaf, &proof, block.transactions_root));
+    }
+
+    #[test]
+    fn proof_is_none_for_out_of_range_index() {
+        let block = block_with_txs(4);
+
+        assert!(block.merkle_proof(4).is_none());
+    }
+}
+
+#[cfg(test)]
+mod merkle_root_tests {
+    use super::*;
+
+    fn block_with_txs(count: u32) -> Block {
+        let mut block = Block::new(Bytes32::default(), INITIAL_DIFFICULTY, 0);
+        let transactions = (0..count)
+            .map(|value| Transaction {
+                value,
+                ..Default::default()
+            })
+            .collect();
+
+        block.set_transactions(transactions);
+        block
+    }
+
+    #[test]
+    fn empty_block_root_is_zero() {
+        assert_eq!(block_with_txs(0).merkle_root(), Bytes32::default());
+    }
+
+    #[test]
+    fn single_transaction_root_is_its_hash() {
+        let block = block_with_txs(1);
+        let leaf = keccak256(&bincode::serialize(&block.transactions[0]).unwrap());
+
+        assert_eq!(block.merkle_root(), leaf);
+    }
+
+    #[test]
+    fn odd_count_duplicates_the_last_leaf() {
+        let odd = block_with_txs(3);
+
+        // Duplicating the odd block's last transaction should reproduce the
+        // same root, since `merkle_root` folds an odd-sized level by
+        // duplicating its last node.
+        let mut padded_transactions = odd.transactions.clone();
+        padded_transactions.push(padded_transactions.last().unwrap().clone());
+
+        let mut padded = Block::new(Bytes32::default(), INITIAL_DIFFICULTY, 0);
+        padded.set_transactions(padded_transactions);
+
+        assert_eq!(odd.merkle_root(), padded.merkle_root());
+    }
+
+    #[test]
+    fn root_changes_when_a_transaction_changes() {
+        let a = block_with_txs(3);
+        let mut transactions = a.transactions.clone();
+        transactions[1].value += 1;
+
+        let mut b = Block::new(Bytes32::default(), INITIAL_DIFFICULTY, 0);
+        b.set_transactions(transactions);
+
+        assert_ne!(a.merkle_root(), b.merkle_root());
+    }
+}
+
+#[cfg(test)]
+mod state_tests {
+    use super::*;
+
+    fn address(byte: u8) -> Address {
+        Bytes([byte; 20])
+    }
+
+    #[test]
+    fn apply_transaction_moves_balance_and_bumps_nonce() {
+        let mut state = State::new();
+        let alice = address(1);
+        let bob = address(2);
+        state.credit(&alice, 100);
+
+        let applied = state.apply_transaction(&Transaction {
+            from: alice,
+            to: bob,
+            value: 40,
+            hash: Bytes32::default(),
+        });
+
+        assert!(applied);
+        assert_eq!(state.get_balance(&alice), 60);
+        assert_eq!(state.get_balance(&bob), 40);
+        assert_eq!(state.get_account(&alice).nonce, 1);
+    }
+
+    #[test]
+    fn apply_transaction_rejects_insufficient_balance() {
+        let mut state = State::new();
+        let alice = address(1);
+        let bob = address(2);
+        state.credit(&alice, 10);
+
+        let applied = state.apply_transaction(&Transaction {
+            from: alice,
+            to: bob,
+            value: 40,
+            hash: Bytes32::default(),
+        });
+
+        assert!(!applied);
+        assert_eq!(state.get_balance(&alice), 10);
+        assert_eq!(state.get_balance(&bob), 0);
+    }
+
+    #[test]
+    fn empty_state_root_is_zero() {
+        assert_eq!(State::new().state_root(), Bytes32::default());
+    }
+
+    #[test]
+    fn state_root_is_independent_of_credit_order() {
+        let alice = address(1);
+        let bob = address(2);
+
+        let mut first = State::new();
+        first.credit(&alice, 10);
+        first.credit(&bob, 20);
+
+        let mut second = State::new();
+        second.credit(&bob, 20);
+        second.credit(&alice, 10);
+
+        assert_eq!(first.state_root(), second.state_root());
+    }
+
+    #[test]
+    fn state_root_changes_with_balance() {
+        let alice = address(1);
+
+        let mut state = State::new();
+        state.credit(&alice, 10);
+        let before = state.state_root();
+
+        state.credit(&alice, 1);
+
+        assert_ne!(before, state.state_root());
+    }
+}
+
+#[cfg(test)]
+mod block_by_hash_tests {
+    use super::*;
+
+    fn genesis_block(difficulty: u64) -> Block {
+        let mut block = Block::new(Bytes32::default(), difficulty, 0);
+        block.hash = block.hash_block(0);
+        block
+    }
+
+    fn config() -> Config {
+        Config {
+            genesis_file: None,
+            datadir: None,
+            target_secs: DEFAULT_TARGET_SECS,
+            minimum_difficulty: INITIAL_DIFFICULTY,
+            difficulty_bound_divisor: DEFAULT_DIFFICULTY_BOUND_DIVISOR,
+        }
+    }
+
+    #[test]
+    fn finds_genesis_by_its_hash() {
+        let genesis = genesis_block(INITIAL_DIFFICULTY);
+        let genesis_hash = genesis.hash;
+        let blockchain = Blockchain::new(genesis, State::new(), &config());
+
+        assert_eq!(blockchain.block_by_hash(&genesis_hash).unwrap().hash, genesis_hash);
+    }
+
+    #[test]
+    fn finds_a_mined_block_by_its_hash() {
+        let mut blockchain = Blockchain::new(genesis_block(INITIAL_DIFFICULTY), State::new(), &config());
+        let mined = blockchain.add_new_block();
+
+        assert_eq!(blockchain.block_by_hash(&mined.hash).unwrap().hash, mined.hash);
+    }
+
+    #[test]
+    fn returns_none_for_an_unknown_hash() {
+        let blockchain = Blockchain::new(genesis_block(INITIAL_DIFFICULTY), State::new(), &config());
+        let unknown = Bytes32::default();
+
+        // The genesis block's own hash is never the zero hash once mined/hashed,
+        // so this is a safe stand-in for "hash not in the chain".
+        assert_ne!(blockchain.chain[0].hash, unknown);
+        assert!(blockchain.block_by_hash(&unknown).is_none());
+    }
+}